@@ -1,9 +1,11 @@
 use std::{char, collections::HashMap};
 
 use crate::{
-    Address, Program, ZeroPageAdd, ZeroPageAnd, ZeroPageImmediateLoad, ZeroPageLoad,
-    ZeroPageLoadIfPos, ZeroPageNegate, ZeroPageOr, ZeroPageStore, ZeroPageXor,
+    inst_len, Address, Inst, Opcodes, Program, ZeroPageAdd, ZeroPageAnd, ZeroPageDivRem,
+    ZeroPageImmediateLoad, ZeroPageImmediateLoad32, ZeroPageLoad, ZeroPageLoadIfPos, ZeroPageMul,
+    ZeroPageNegate, ZeroPageOr, ZeroPageStore, ZeroPageXor,
 };
+use num_traits::FromPrimitive;
 
 struct ParseState {
     symbols: HashMap<String, Address>,
@@ -66,6 +68,21 @@ fn add_inst(line: &str, program: Program, symbols: Option<&HashMap<String, Addre
             addr: operand(1) as u8,
             imm: operand(2) as u16,
         }),
+        "LI32" => program.push(ZeroPageImmediateLoad32 {
+            addr: operand(1) as u8,
+            imm: operand(2) as u32,
+        }),
+        "MUL" => program.push(ZeroPageMul {
+            lhs: operand(1) as u8,
+            rhs: operand(2) as u8,
+            out: operand(3) as u8,
+        }),
+        "DIVREM" => program.push(ZeroPageDivRem {
+            lhs: operand(1) as u8,
+            rhs: operand(2) as u8,
+            quot: operand(3) as u8,
+            rem: operand(4) as u8,
+        }),
         "LP" => program.push(ZeroPageLoadIfPos {
             cond: operand(1) as u8,
             from: operand(2) as u8,
@@ -75,7 +92,7 @@ fn add_inst(line: &str, program: Program, symbols: Option<&HashMap<String, Addre
     }
 }
 
-pub fn assemble(asm: String) -> (Program, Vec<Address>) {
+pub fn assemble(asm: String) -> (Program, Vec<Address>, HashMap<String, Address>) {
     let ParseState {
         symbols,
         breakpoints,
@@ -121,7 +138,9 @@ pub fn assemble(asm: String) -> (Program, Vec<Address>) {
         },
     );
 
-    let ParseState { program, .. } = asm.split('\n').fold(
+    let ParseState {
+        program, symbols, ..
+    } = asm.split('\n').fold(
         ParseState {
             program: Program::new(),
             symbols,
@@ -153,5 +172,141 @@ pub fn assemble(asm: String) -> (Program, Vec<Address>) {
         },
     );
 
-    (program, breakpoints)
+    (program, breakpoints, symbols)
+}
+
+/// Reverse-resolve `addr` against a symbol table, falling back to a hex
+/// literal when no label is known for it (or none was supplied).
+fn operand_text(addr: u16, symbols: Option<&HashMap<String, Address>>) -> String {
+    symbols
+        .and_then(|symbols| symbols.iter().find(|(_, a)| **a == addr))
+        .map(|(name, _)| format!(":{name}"))
+        .unwrap_or_else(|| format!("0x{addr:x}"))
+}
+
+/// Walk a stream of program words back into the assembly text `add_inst`
+/// understands, advancing by each instruction's length so VLE instructions
+/// are handled correctly.
+pub fn disassemble(words: &[u32], symbols: Option<&HashMap<String, Address>>) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut i = 0;
+
+    while i < words.len() {
+        let opcode = words[i].to_be_bytes()[0];
+        let length = inst_len(opcode);
+        let chunk = &words[i..i + length];
+
+        let line = match Opcodes::from_u8(opcode) {
+            Some(Opcodes::ZeroPageAdd) => {
+                let inst = ZeroPageAdd::from_bytes(chunk.try_into().unwrap());
+                format!(
+                    "ADD {} {} {}",
+                    operand_text(inst.lhs as u16, symbols),
+                    operand_text(inst.rhs as u16, symbols),
+                    operand_text(inst.out as u16, symbols)
+                )
+            }
+            Some(Opcodes::ZeroPageNeg) => {
+                let inst = ZeroPageNegate::from_bytes(chunk.try_into().unwrap());
+                format!(
+                    "NEG {} {}",
+                    operand_text(inst.input as u16, symbols),
+                    operand_text(inst.out as u16, symbols)
+                )
+            }
+            Some(Opcodes::ZeroPageAnd) => {
+                let inst = ZeroPageAnd::from_bytes(chunk.try_into().unwrap());
+                format!(
+                    "AND {} {} {}",
+                    operand_text(inst.lhs as u16, symbols),
+                    operand_text(inst.rhs as u16, symbols),
+                    operand_text(inst.out as u16, symbols)
+                )
+            }
+            Some(Opcodes::ZeroPageOr) => {
+                let inst = ZeroPageOr::from_bytes(chunk.try_into().unwrap());
+                format!(
+                    "OR {} {} {}",
+                    operand_text(inst.lhs as u16, symbols),
+                    operand_text(inst.rhs as u16, symbols),
+                    operand_text(inst.out as u16, symbols)
+                )
+            }
+            Some(Opcodes::ZeroPageXor) => {
+                let inst = ZeroPageXor::from_bytes(chunk.try_into().unwrap());
+                format!(
+                    "XOR {} {} {}",
+                    operand_text(inst.lhs as u16, symbols),
+                    operand_text(inst.rhs as u16, symbols),
+                    operand_text(inst.out as u16, symbols)
+                )
+            }
+            Some(Opcodes::ZeroPageLoad) => {
+                let inst = ZeroPageLoad::from_bytes(chunk.try_into().unwrap());
+                format!(
+                    "L {} {}",
+                    operand_text(inst.from, symbols),
+                    operand_text(inst.to as u16, symbols)
+                )
+            }
+            Some(Opcodes::ZeroPageStore) => {
+                let inst = ZeroPageStore::from_bytes(chunk.try_into().unwrap());
+                format!(
+                    "S {} {}",
+                    operand_text(inst.from as u16, symbols),
+                    operand_text(inst.to, symbols)
+                )
+            }
+            Some(Opcodes::ZeroPageImmediateLoad) => {
+                let inst = ZeroPageImmediateLoad::from_bytes(chunk.try_into().unwrap());
+                format!(
+                    "LI {} {}",
+                    operand_text(inst.addr as u16, symbols),
+                    operand_text(inst.imm, symbols)
+                )
+            }
+            Some(Opcodes::ZeroPageImmediateLoad32) => {
+                let inst = ZeroPageImmediateLoad32::from_bytes(chunk.try_into().unwrap());
+                let imm = match u16::try_from(inst.imm) {
+                    Ok(imm) => operand_text(imm, symbols),
+                    Err(_) => format!("0x{:x}", inst.imm),
+                };
+                format!("LI32 {} {imm}", operand_text(inst.addr as u16, symbols))
+            }
+            Some(Opcodes::ZeroPageLoadIfPos) => {
+                let inst = ZeroPageLoadIfPos::from_bytes(chunk.try_into().unwrap());
+                format!(
+                    "LP {} {} {}",
+                    operand_text(inst.cond as u16, symbols),
+                    operand_text(inst.from as u16, symbols),
+                    operand_text(inst.to as u16, symbols)
+                )
+            }
+            Some(Opcodes::ZeroPageMul) => {
+                let inst = ZeroPageMul::from_bytes(chunk.try_into().unwrap());
+                format!(
+                    "MUL {} {} {}",
+                    operand_text(inst.lhs as u16, symbols),
+                    operand_text(inst.rhs as u16, symbols),
+                    operand_text(inst.out as u16, symbols)
+                )
+            }
+            Some(Opcodes::ZeroPageDivRem) => {
+                let inst = ZeroPageDivRem::from_bytes(chunk.try_into().unwrap());
+                format!(
+                    "DIVREM {} {} {} {}",
+                    operand_text(inst.lhs as u16, symbols),
+                    operand_text(inst.rhs as u16, symbols),
+                    operand_text(inst.quot as u16, symbols),
+                    operand_text(inst.rem as u16, symbols)
+                )
+            }
+            None => format!("; unknown opcode 0b{opcode:08b}"),
+        };
+
+        lines.push(line);
+        i += length;
+    }
+
+    lines
 }
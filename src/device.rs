@@ -0,0 +1,156 @@
+use std::ops::RangeInclusive;
+
+use crate::Address;
+
+/// A memory-mapped peripheral.
+///
+/// A `Device` claims a contiguous range of the address space; `Machine`
+/// dispatches any read/write that falls inside that range to the device
+/// instead of the backing memory array.
+pub trait Device {
+    fn range(&self) -> RangeInclusive<Address>;
+    fn read(&self, offset: Address) -> u32;
+    fn write(&mut self, offset: Address, value: u32);
+
+    /// Called once per executed instruction with the cycle cost just spent.
+    /// Returns `true` if the device wants to raise an interrupt this tick.
+    fn tick(&mut self, _cycles: u64) -> bool {
+        false
+    }
+
+    /// Called by the debugger's `fb` command. Devices with nothing
+    /// interesting to show can leave this as a no-op.
+    fn dump(&self) {}
+}
+
+/// The original `WRITING`/`DATA` console, now a device instead of a
+/// special case polled after every instruction: writing a non-zero word to
+/// `WRITING` prints the low byte of whatever was last written to `DATA`.
+pub struct Console {
+    base: Address,
+    data: u32,
+}
+
+impl Console {
+    /// `base` is the address of the `WRITING` register; `DATA` is `base + 1`.
+    pub fn new(base: Address) -> Self {
+        Self { base, data: 0 }
+    }
+}
+
+impl Device for Console {
+    fn range(&self) -> RangeInclusive<Address> {
+        self.base..=self.base + 1
+    }
+
+    fn read(&self, offset: Address) -> u32 {
+        match offset {
+            0 => 0,
+            1 => self.data,
+            _ => unreachable!("offset outside device range"),
+        }
+    }
+
+    fn write(&mut self, offset: Address, value: u32) {
+        match offset {
+            0 => {
+                if value != 0 {
+                    print!("{}", self.data as u8 as char);
+                    std::io::Write::flush(&mut std::io::stdout()).unwrap();
+                }
+            }
+            1 => self.data = value,
+            _ => unreachable!("offset outside device range"),
+        }
+    }
+}
+
+/// A grid of character cells that can be dumped to stdout, so programs can
+/// display text for real instead of streaming it through the console.
+pub struct TextFramebuffer {
+    base: Address,
+    width: usize,
+    height: usize,
+    cells: Vec<u8>,
+}
+
+impl TextFramebuffer {
+    pub fn new(base: Address, width: usize, height: usize) -> Self {
+        Self {
+            base,
+            width,
+            height,
+            cells: vec![b' '; width * height],
+        }
+    }
+}
+
+impl Device for TextFramebuffer {
+    fn range(&self) -> RangeInclusive<Address> {
+        self.base..=self.base + (self.width * self.height) as Address - 1
+    }
+
+    fn read(&self, offset: Address) -> u32 {
+        self.cells[offset as usize] as u32
+    }
+
+    fn write(&mut self, offset: Address, value: u32) {
+        self.cells[offset as usize] = value as u8;
+    }
+
+    fn dump(&self) {
+        for row in self.cells.chunks(self.width) {
+            println!("{}", String::from_utf8_lossy(row));
+        }
+    }
+}
+
+/// A wrap-around countdown timer: writing a reload value starts it
+/// counting down by one per executed cycle; each time it reaches zero it
+/// wraps back to the reload value and raises an interrupt.
+pub struct Timer {
+    base: Address,
+    reload: u32,
+    counter: u32,
+}
+
+impl Timer {
+    pub fn new(base: Address) -> Self {
+        Self {
+            base,
+            reload: 0,
+            counter: 0,
+        }
+    }
+}
+
+impl Device for Timer {
+    fn range(&self) -> RangeInclusive<Address> {
+        self.base..=self.base
+    }
+
+    fn read(&self, _offset: Address) -> u32 {
+        self.counter
+    }
+
+    fn write(&mut self, _offset: Address, value: u32) {
+        self.reload = value;
+        self.counter = value;
+    }
+
+    fn tick(&mut self, cycles: u64) -> bool {
+        if self.reload == 0 {
+            return false;
+        }
+
+        let mut fired = false;
+        for _ in 0..cycles {
+            self.counter -= 1;
+            if self.counter == 0 {
+                self.counter = self.reload;
+                fired = true;
+            }
+        }
+        fired
+    }
+}
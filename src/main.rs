@@ -1,10 +1,12 @@
 mod asm;
+mod device;
 
-use std::{io::Write, time::Instant};
+use std::{collections::HashMap, io::Write, time::Instant};
 
-use asm::assemble;
+use asm::{assemble, disassemble};
 use bitint::prelude::*;
 use bytemuck::Pod;
+use device::{Console, Device, TextFramebuffer, Timer};
 use num_derive::{FromPrimitive, ToPrimitive};
 use num_traits::{FromPrimitive, ToBytes, ToPrimitive};
 
@@ -13,6 +15,37 @@ pub const PC: Address = 0x0;
 pub const WRITING: Address = 0xFFFE;
 pub const DATA: Address = 0xFFFF;
 
+/// Address of the faulting PC save slot, written before a trap handler runs.
+pub const TRAP_PC_SAVE: Address = 0xFFFB;
+/// Address of the trap handler vector. A zero vector means "halt".
+pub const TRAP_VECTOR: Address = 0xFFFC;
+/// Address of the IRQ handler vector, taken at the next instruction boundary.
+pub const IRQ_VECTOR: Address = 0xFFFA;
+/// Address of the built-in countdown timer's reload register.
+pub const TIMER: Address = 0xFFF9;
+/// Base address of the built-in 40x25 text framebuffer.
+pub const FRAMEBUFFER: Address = 0xF000;
+
+/// Something that stopped `Machine::step` from completing normally.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExecutionFault {
+    InvalidOpcode(u8),
+    OutOfBounds(Address),
+    DivideByZero,
+}
+
+impl std::fmt::Display for ExecutionFault {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::InvalidOpcode(opcode) => write!(f, "invalid opcode 0b{opcode:08b}"),
+            Self::OutOfBounds(addr) => write!(f, "instruction at 0x{addr:04x} runs out of bounds"),
+            Self::DivideByZero => write!(f, "division by zero"),
+        }
+    }
+}
+
+impl std::error::Error for ExecutionFault {}
+
 #[derive(FromPrimitive, ToPrimitive)]
 pub enum Opcodes {
     ZeroPageAdd = 0b0000_0000,
@@ -24,6 +57,49 @@ pub enum Opcodes {
     ZeroPageStore = 0b0000_1001,
     ZeroPageImmediateLoad = 0b0000_1010,
     ZeroPageLoadIfPos = 0b0000_1100,
+    ZeroPageImmediateLoad32 = 0b0000_1011,
+    ZeroPageMul = 0b0000_1101,
+    ZeroPageDivRem = 0b0000_1111,
+}
+
+/// Number of `u32` words a given opcode occupies, keyed by opcode byte.
+///
+/// Opcodes with the low two bits set are variable-length; their length is
+/// looked up here instead of being hard-coded to 1.
+fn inst_len(opcode: u8) -> usize {
+    match Opcodes::from_u8(opcode) {
+        Some(Opcodes::ZeroPageImmediateLoad32) => 2,
+        Some(Opcodes::ZeroPageDivRem) => 2,
+        _ => 1,
+    }
+}
+
+/// Cost in cycles of executing a given opcode. Multiply/divide cost more
+/// than the rest of the ALU, which all run in a single cycle.
+fn opcode_cost(opcode: u8) -> u64 {
+    match Opcodes::from_u8(opcode) {
+        Some(Opcodes::ZeroPageMul) | Some(Opcodes::ZeroPageDivRem) => 2,
+        _ => 1,
+    }
+}
+
+/// Render a word as binary, grouped into nibbles/bytes, for the debugger.
+fn format_word(value: u32) -> String {
+    format!("{value:032b}")
+        .chars()
+        .enumerate()
+        .map(|(i, c)| {
+            c.to_string()
+                + if i % 8 == 7 {
+                    "  "
+                } else if i % 4 == 3 {
+                    " "
+                } else {
+                    ""
+                }
+        })
+        .collect::<Vec<String>>()
+        .join("")
 }
 
 pub trait Inst<const N: usize> {
@@ -194,6 +270,87 @@ impl Inst<1> for ZeroPageImmediateLoad {
     }
 }
 
+#[derive(Clone, Copy, Debug)]
+pub struct ZeroPageImmediateLoad32 {
+    addr: u8,
+    imm: u32,
+}
+
+impl Inst<2> for ZeroPageImmediateLoad32 {
+    fn from_bytes(bytes: &[u32; 2]) -> Self {
+        let header = bytes[0].to_be_bytes();
+        assert_eq!(header[0], Opcodes::ZeroPageImmediateLoad32 as u8);
+        Self {
+            addr: header[1],
+            imm: u32::from_le_bytes(bytes[1].to_le_bytes()),
+        }
+    }
+
+    fn to_bytes(&self) -> [u32; 2] {
+        [
+            u32::from_be_bytes([Opcodes::ZeroPageImmediateLoad32 as u8, self.addr, 0, 0]),
+            u32::from_le_bytes(self.imm.to_le_bytes()),
+        ]
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct ZeroPageMul {
+    lhs: u8,
+    rhs: u8,
+    out: u8,
+}
+
+impl Inst<1> for ZeroPageMul {
+    fn from_bytes(bytes: &[u32; 1]) -> Self {
+        let bytes = bytes[0].to_be_bytes();
+        assert_eq!(bytes[0], Opcodes::ZeroPageMul as u8);
+        Self {
+            lhs: bytes[1],
+            rhs: bytes[2],
+            out: bytes[3],
+        }
+    }
+
+    fn to_bytes(&self) -> [u32; 1] {
+        [u32::from_be_bytes([
+            Opcodes::ZeroPageMul as u8,
+            self.lhs,
+            self.rhs,
+            self.out,
+        ])]
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct ZeroPageDivRem {
+    lhs: u8,
+    rhs: u8,
+    quot: u8,
+    rem: u8,
+}
+
+impl Inst<2> for ZeroPageDivRem {
+    fn from_bytes(bytes: &[u32; 2]) -> Self {
+        let header = bytes[0].to_be_bytes();
+        assert_eq!(header[0], Opcodes::ZeroPageDivRem as u8);
+        let tail = bytes[1].to_be_bytes();
+        Self {
+            lhs: header[1],
+            rhs: header[2],
+            quot: tail[0],
+            rem: tail[1],
+        }
+    }
+
+    fn to_bytes(&self) -> [u32; 2] {
+        [
+            u32::from_be_bytes([Opcodes::ZeroPageDivRem as u8, self.lhs, self.rhs, 0]),
+            u32::from_be_bytes([self.quot, self.rem, 0, 0]),
+        ]
+    }
+}
+
 pub struct ZeroPageLoad {
     from: u16,
     to: u8,
@@ -273,19 +430,39 @@ impl Inst<1> for ZeroPageLoadIfPos {
     }
 }
 
-#[derive(Clone, Debug)]
 pub struct Machine {
     memory: [u32; 2_usize.pow(16)],
     breakpoints: Vec<Address>,
+    devices: Vec<Box<dyn Device>>,
+    /// Pending IRQ flag. There's no opcode to mask it, so a raised IRQ is
+    /// always taken at the next instruction boundary.
+    irq: bool,
+    cycles: u64,
+    symbols: HashMap<String, Address>,
 }
 
 impl Machine {
+    fn device_for(&self, addr: Address) -> Option<usize> {
+        self.devices
+            .iter()
+            .position(|device| device.range().contains(&addr))
+    }
+
     pub fn read(&self, addr: Address) -> u32 {
-        self.memory[addr as usize]
+        match self.device_for(addr) {
+            Some(i) => self.devices[i].read(addr - *self.devices[i].range().start()),
+            None => self.memory[addr as usize],
+        }
     }
 
     pub fn write(&mut self, addr: Address, value: u32) {
-        self.memory[addr as usize] = value
+        match self.device_for(addr) {
+            Some(i) => {
+                let offset = addr - *self.devices[i].range().start();
+                self.devices[i].write(offset, value)
+            }
+            None => self.memory[addr as usize] = value,
+        }
     }
 
     pub fn read_n(&self, addr: Address, len: usize) -> &[u32] {
@@ -324,9 +501,150 @@ impl Machine {
         self
     }
 
+    pub fn device(mut self, dev: Box<dyn Device>) -> Self {
+        self.devices.push(dev);
+
+        self
+    }
+
+    /// Attach the symbol table `assemble` produced, so the debugger can
+    /// resolve addresses back to labels (see the `d`/`disasm` command).
+    pub fn symbols(mut self, symbols: HashMap<String, Address>) -> Self {
+        self.symbols = symbols;
+
+        self
+    }
+
+    /// Signal an interrupt request. It's taken at the next instruction
+    /// boundary.
+    pub fn raise_irq(&mut self) {
+        self.irq = true;
+    }
+
+    /// Total cycles executed so far.
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    /// Execute exactly one instruction.
+    pub fn step(&mut self) -> Result<(), ExecutionFault> {
+        let pc: Address = self.read(PC) as u16;
+
+        let inst = self.read(pc);
+        let bytes = inst.to_be_bytes();
+        let opcode = bytes[0];
+        let length = inst_len(opcode);
+
+        if pc as usize + length > self.memory.len() {
+            return Err(ExecutionFault::OutOfBounds(pc));
+        }
+
+        let inst = self.read_n(pc, length);
+        let decoded = Opcodes::from_u8(opcode).ok_or(ExecutionFault::InvalidOpcode(opcode))?;
+
+        match decoded {
+            Opcodes::ZeroPageAdd => {
+                let inst = ZeroPageAdd::from_bytes(inst.try_into().unwrap());
+                self.write(
+                    inst.out as Address,
+                    self.read(inst.lhs as Address) + self.read(inst.rhs as Address),
+                );
+            }
+            Opcodes::ZeroPageNeg => {
+                let inst = ZeroPageNegate::from_bytes(inst.try_into().unwrap());
+                self.write(
+                    inst.out as Address,
+                    bytemuck::cast::<i32, u32>(
+                        -(bytemuck::cast::<u32, i32>(self.read(inst.input as Address))),
+                    ),
+                )
+            }
+            Opcodes::ZeroPageAnd => {
+                let inst = ZeroPageAnd::from_bytes(inst.try_into().unwrap());
+                self.write(
+                    inst.out as Address,
+                    self.read(inst.lhs as Address) & self.read(inst.rhs as Address),
+                );
+            }
+            Opcodes::ZeroPageOr => {
+                let inst = ZeroPageOr::from_bytes(inst.try_into().unwrap());
+                self.write(
+                    inst.out as Address,
+                    self.read(inst.lhs as Address) | self.read(inst.rhs as Address),
+                );
+            }
+            Opcodes::ZeroPageXor => {
+                let inst = ZeroPageXor::from_bytes(inst.try_into().unwrap());
+                self.write(
+                    inst.out as Address,
+                    self.read(inst.lhs as Address) ^ self.read(inst.rhs as Address),
+                );
+            }
+            Opcodes::ZeroPageImmediateLoad => {
+                let inst = ZeroPageImmediateLoad::from_bytes(inst.try_into().unwrap());
+                self.write(inst.addr as Address, inst.imm as u32);
+            }
+            Opcodes::ZeroPageLoad => {
+                let inst = ZeroPageLoad::from_bytes(inst.try_into().unwrap());
+                self.write(inst.to as Address, self.read(inst.from as Address))
+            }
+            Opcodes::ZeroPageStore => {
+                let inst = ZeroPageStore::from_bytes(inst.try_into().unwrap());
+                self.write(inst.to as Address, self.read(inst.from as Address))
+            }
+            Opcodes::ZeroPageLoadIfPos => {
+                let inst = ZeroPageLoadIfPos::from_bytes(inst.try_into().unwrap());
+                if bytemuck::cast::<u32, i32>(self.read(inst.cond as Address)) > 0 {
+                    self.write(inst.to as Address, self.read(inst.from as Address));
+                }
+            }
+            Opcodes::ZeroPageImmediateLoad32 => {
+                let inst = ZeroPageImmediateLoad32::from_bytes(inst.try_into().unwrap());
+                self.write(inst.addr as Address, inst.imm);
+            }
+            Opcodes::ZeroPageMul => {
+                let inst = ZeroPageMul::from_bytes(inst.try_into().unwrap());
+                self.write(
+                    inst.out as Address,
+                    bytemuck::cast::<i32, u32>(
+                        bytemuck::cast::<u32, i32>(self.read(inst.lhs as Address))
+                            .wrapping_mul(bytemuck::cast::<u32, i32>(
+                                self.read(inst.rhs as Address),
+                            )),
+                    ),
+                );
+            }
+            Opcodes::ZeroPageDivRem => {
+                let inst = ZeroPageDivRem::from_bytes(inst.try_into().unwrap());
+                let lhs = bytemuck::cast::<u32, i32>(self.read(inst.lhs as Address));
+                let rhs = bytemuck::cast::<u32, i32>(self.read(inst.rhs as Address));
+                if rhs == 0 {
+                    return Err(ExecutionFault::DivideByZero);
+                }
+                let (quot, rem) = (lhs.wrapping_div(rhs), lhs.wrapping_rem(rhs));
+                self.write(inst.quot as Address, bytemuck::cast::<i32, u32>(quot));
+                self.write(inst.rem as Address, bytemuck::cast::<i32, u32>(rem));
+            }
+        }
+
+        if pc == self.read(PC) as u16 {
+            self.write(PC, pc as u32 + length as u32);
+        }
+
+        let cost = opcode_cost(opcode);
+        self.cycles += cost;
+        for device in &mut self.devices {
+            if device.tick(cost) {
+                self.irq = true;
+            }
+        }
+
+        Ok(())
+    }
+
     pub fn run(mut self) -> Self {
         let mut start = Instant::now();
-        let mut i = 0;
+        let mut last_cycles = self.cycles;
         loop {
             let pc: Address = self.read(PC) as u16;
 
@@ -334,91 +652,30 @@ impl Machine {
                 self.debug()
             }
 
-            let inst = self.read(pc);
-            let bytes = inst.to_be_bytes();
-            let opcode = bytes[0];
-            //println!("{:08b}", opcode);
-            let length = if (opcode & 0b11) == 0b11 {
-                // VLE
-                todo!("VLE is not yet supported")
-            } else {
-                1
-            };
-            let inst = self.read_n(pc, length);
-
-            match Opcodes::from_u8(opcode).unwrap() {
-                Opcodes::ZeroPageAdd => {
-                    let inst = ZeroPageAdd::from_bytes(inst.try_into().unwrap());
-                    self.write(
-                        inst.out as Address,
-                        self.read(inst.lhs as Address) + self.read(inst.rhs as Address),
-                    );
-                }
-                Opcodes::ZeroPageNeg => {
-                    let inst = ZeroPageNegate::from_bytes(inst.try_into().unwrap());
-                    self.write(
-                        inst.out as Address,
-                        bytemuck::cast::<i32, u32>(
-                            -(bytemuck::cast::<u32, i32>(self.read(inst.input as Address))),
-                        ),
-                    )
-                }
-                Opcodes::ZeroPageAnd => {
-                    let inst = ZeroPageAnd::from_bytes(inst.try_into().unwrap());
-                    self.write(
-                        inst.out as Address,
-                        self.read(inst.lhs as Address) & self.read(inst.rhs as Address),
-                    );
-                }
-                Opcodes::ZeroPageOr => {
-                    let inst = ZeroPageOr::from_bytes(inst.try_into().unwrap());
-                    self.write(
-                        inst.out as Address,
-                        self.read(inst.lhs as Address) | self.read(inst.rhs as Address),
-                    );
-                }
-                Opcodes::ZeroPageXor => {
-                    let inst = ZeroPageXor::from_bytes(inst.try_into().unwrap());
-                    self.write(
-                        inst.out as Address,
-                        self.read(inst.lhs as Address) ^ self.read(inst.rhs as Address),
-                    );
-                }
-                Opcodes::ZeroPageImmediateLoad => {
-                    let inst = ZeroPageImmediateLoad::from_bytes(inst.try_into().unwrap());
-                    self.write(inst.addr as Address, inst.imm as u32);
-                }
-                Opcodes::ZeroPageLoad => {
-                    let inst = ZeroPageLoad::from_bytes(inst.try_into().unwrap());
-                    self.write(inst.to as Address, self.read(inst.from as Address))
-                }
-                Opcodes::ZeroPageStore => {
-                    let inst = ZeroPageStore::from_bytes(inst.try_into().unwrap());
-                    self.write(inst.to as Address, self.read(inst.from as Address))
-                }
-                Opcodes::ZeroPageLoadIfPos => {
-                    let inst = ZeroPageLoadIfPos::from_bytes(inst.try_into().unwrap());
-                    if bytemuck::cast::<u32, i32>(self.read(inst.cond as Address)) > 0 {
-                        self.write(inst.to as Address, self.read(inst.from as Address));
+            match self.step() {
+                Ok(()) => {}
+                Err(fault) => {
+                    let vector = self.read(TRAP_VECTOR) as Address;
+                    if vector == 0 {
+                        eprintln!("Unhandled fault ({fault}) at 0x{pc:04x}, halting");
+                        return self;
                     }
+                    self.write(TRAP_PC_SAVE, pc as u32);
+                    self.write(PC, vector as u32);
                 }
             }
 
-            if self.read(WRITING) != 0 {
-                print!("{}", self.read(DATA) as u8 as char);
-                std::io::stdout().flush().unwrap();
-                self.write(WRITING, 0);
-            }
-
-            if pc == self.read(PC) as u16 {
-                self.write(PC, pc as u32 + length as u32);
+            if self.irq {
+                self.irq = false;
+                let pc = self.read(PC);
+                self.write(TRAP_PC_SAVE, pc);
+                self.write(PC, self.read(IRQ_VECTOR));
             }
 
-            i += 1;
-            if i == 100_000_000 {
+            if self.cycles - last_cycles >= 100_000_000 {
                 println!("{}MHz", 100. / (Instant::now() - start).as_secs_f64());
                 start = Instant::now();
-                i = 0;
+                last_cycles = self.cycles;
             }
         }
     }
@@ -433,30 +690,62 @@ impl Machine {
             std::io::stdin().read_line(&mut cmd).unwrap();
             let cmd = cmd.trim();
 
-            let opcode = cmd.split(' ').nth(0).unwrap();
+            let mut words = cmd.split(' ');
+            let opcode = words.next().unwrap_or("");
             match opcode {
                 "c" | "cont" | "continue" => break,
+                "s" | "step" => match self.step() {
+                    Ok(()) => println!("0x{:04x}", self.read(PC)),
+                    Err(fault) => println!("Fault: {fault}"),
+                },
                 "r" | "read" => println!(
                     "{}",
-                    format!(
-                        "{:032b}",
-                        self.read(
-                            Address::from_str_radix(cmd.split(' ').nth(1).unwrap(), 16).unwrap()
-                        )
+                    format_word(
+                        self.read(Address::from_str_radix(words.next().unwrap(), 16).unwrap())
                     )
-                    .chars()
-                    .enumerate()
-                    .map(|(i, c)| c.to_string()
-                        + if i % 8 == 7 {
-                            "  "
-                        } else if i % 4 == 3 {
-                            " "
-                        } else {
-                            ""
-                        })
-                    .collect::<Vec<String>>()
-                    .join("")
                 ),
+                "w" | "write" => {
+                    let addr = Address::from_str_radix(words.next().unwrap(), 16).unwrap();
+                    let value = u32::from_str_radix(words.next().unwrap(), 16).unwrap();
+                    self.write(addr, value);
+                }
+                "d" | "disasm" => {
+                    let mut addr = Address::from_str_radix(words.next().unwrap(), 16).unwrap();
+                    let count: usize = words.next().and_then(|n| n.parse().ok()).unwrap_or(1);
+                    for _ in 0..count {
+                        let length = inst_len(self.read(addr).to_be_bytes()[0]);
+                        if addr as usize + length > self.memory.len() {
+                            println!("0x{addr:04x}  ; out of bounds");
+                            break;
+                        }
+                        let inst = self.read_n(addr, length).to_vec();
+                        for line in disassemble(&inst, Some(&self.symbols)) {
+                            println!("0x{addr:04x}  {line}");
+                        }
+                        match addr.checked_add(length as Address) {
+                            Some(next) => addr = next,
+                            None => break,
+                        }
+                    }
+                }
+                "b" => {
+                    let addr = Address::from_str_radix(words.next().unwrap(), 16).unwrap();
+                    self.breakpoints.push(addr);
+                }
+                "db" => {
+                    let addr = Address::from_str_radix(words.next().unwrap(), 16).unwrap();
+                    self.breakpoints.retain(|&bp| bp != addr);
+                }
+                "fb" => {
+                    for device in &self.devices {
+                        device.dump();
+                    }
+                }
+                "regs" | "info" => {
+                    println!("PC:      {}", format_word(self.read(PC)));
+                    println!("WRITING: {}", format_word(self.read(WRITING)));
+                    println!("DATA:    {}", format_word(self.read(DATA)));
+                }
                 "exit" => panic!("Exitting"),
                 _ => println!("Invalid command"),
             }
@@ -469,6 +758,10 @@ impl Default for Machine {
         Self {
             memory: [0; 2_usize.pow(16)],
             breakpoints: Vec::new(),
+            devices: Vec::new(),
+            irq: false,
+            cycles: 0,
+            symbols: HashMap::new(),
         }
     }
 }
@@ -506,13 +799,17 @@ fn main() {
 
     let mut path = String::new();
     std::io::stdin().read_line(&mut path).unwrap();
-    let (program, breakpoints) =
+    let (program, breakpoints, symbols) =
         assemble(String::from_utf8(std::fs::read(path.trim()).unwrap()).unwrap());
 
     let machine = Machine::default()
         .set(PC, 0x8000)
         .program(&program.bytes(), 0x8000)
-        .breakpoint(0x8000);
+        .breakpoint(0x8000)
+        .device(Box::new(Console::new(WRITING)))
+        .device(Box::new(Timer::new(TIMER)))
+        .device(Box::new(TextFramebuffer::new(FRAMEBUFFER, 40, 25)))
+        .symbols(symbols);
     let machine = breakpoints
         .into_iter()
         .fold(machine, |machine, breakpoint| {